@@ -3,25 +3,120 @@
 // License: GNU General Public License version 3, or any later version
 // See top-level LICENSE file for more information
 
-use swh_graph::SWHID;
+use swh_graph::{NodeType, SWHID};
 
 use std::collections::{HashSet, VecDeque};
 use std::fmt::Display;
 use std::fs::File;
-use std::io::{self, prelude::*, BufReader, BufWriter, Lines};
+use std::io::{self, prelude::*, BufReader, BufWriter};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use anyhow::{Context, Result};
 use clap::Parser;
 use dsi_progress_logger::{progress_logger, ProgressLog};
 use log::{debug, error, info, warn, Level};
+use regex::Regex;
+use sha2::{Digest, Sha256};
 
-use swh_graph::collections::{AdaptiveNodeSet, NodeSet};
 use swh_graph::graph::SwhGraphWithProperties;
-use swh_graph::graph::{self, SwhForwardGraph};
+use swh_graph::graph::{self, SwhBackwardGraph, SwhForwardGraph};
 use swh_graph::mph::DynMphf;
 use swh_graph::properties;
 
+/// Node type to assume for a seed line that is a bare hex object hash (no `swh:` prefix
+/// and thus no type information of its own).
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum HashSeedType {
+    Content,
+    Directory,
+    Revision,
+    Release,
+    Snapshot,
+}
+
+impl From<HashSeedType> for NodeType {
+    fn from(kind: HashSeedType) -> Self {
+        match kind {
+            HashSeedType::Content => NodeType::Content,
+            HashSeedType::Directory => NodeType::Directory,
+            HashSeedType::Revision => NodeType::Revision,
+            HashSeedType::Release => NodeType::Release,
+            HashSeedType::Snapshot => NodeType::Snapshot,
+        }
+    }
+}
+
+/// Direction to expand the traversal frontier in: forward from an origin via
+/// [`SwhForwardGraph::successors`] (the default, "what does this project contain"), or
+/// backward from a seed node via [`SwhBackwardGraph::predecessors`] ("who contains this
+/// artifact").
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+enum Direction {
+    #[default]
+    Forward,
+    Backward,
+}
+
+/// Node type a `--stop-at` flag can target. Unlike [`HashSeedType`], `Origin` is included
+/// since any node type can legitimately be a frontier boundary.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum StopAtNodeType {
+    Content,
+    Directory,
+    Revision,
+    Release,
+    Snapshot,
+    Origin,
+}
+
+impl From<StopAtNodeType> for NodeType {
+    fn from(kind: StopAtNodeType) -> Self {
+        match kind {
+            StopAtNodeType::Content => NodeType::Content,
+            StopAtNodeType::Directory => NodeType::Directory,
+            StopAtNodeType::Revision => NodeType::Revision,
+            StopAtNodeType::Release => NodeType::Release,
+            StopAtNodeType::Snapshot => NodeType::Snapshot,
+            StopAtNodeType::Origin => NodeType::Origin,
+        }
+    }
+}
+
+/// Options controlling how a seed's frontier is expanded: how to resolve a seed line into
+/// a node, which way to walk from it, and where to stop. Bundled into one struct so the
+/// traversal functions below don't need half a dozen loose parameters.
+struct TraversalOptions<G> {
+    allow_protocol_variations: bool,
+    hash_node_type: NodeType,
+    /// `forward_neighbors` or `backward_neighbors`, picked in `main` based on `--direction`
+    /// and on which of `SwhForwardGraph`/`SwhBackwardGraph` the loaded graph supports.
+    neighbors: fn(&G, usize) -> Vec<usize>,
+    stop_at: Option<NodeType>,
+    max_depth: Option<usize>,
+}
+
+// Written by hand rather than derived: `#[derive(Clone, Copy)]` would add a spurious
+// `G: Clone`/`G: Copy` bound, even though every field here is `Copy` regardless of `G`.
+impl<G> Clone for TraversalOptions<G> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<G> Copy for TraversalOptions<G> {}
+
+/// Expands `node` forward to its successors (what does this project contain).
+fn forward_neighbors<G: SwhForwardGraph>(graph: &G, node: usize) -> Vec<usize> {
+    graph.successors(node).collect()
+}
+
+/// Expands `node` backward to its predecessors (who contains this artifact).
+fn backward_neighbors<G: SwhBackwardGraph>(graph: &G, node: usize) -> Vec<usize> {
+    graph.predecessors(node).collect()
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
@@ -29,18 +124,59 @@ struct Args {
     /// names. Check the docs for more details
     #[arg(short, long)]
     graph: PathBuf,
-    /// path to a file with a list of origins to be searched.
-    /// Origins should be one by line, without any extra chars
+    /// path to an extraction manifest listing the seeds to traverse from, one per line. A
+    /// seed line can be an origin URL, a full SWHID (`swh:1:rev:...`), or a bare
+    /// 40-character hex object hash. `%include <path>` splices in another manifest
+    /// (resolved relative to the including file) and `%unset <seed>` removes a
+    /// previously added seed, so a shared base manifest can be layered and overridden
+    /// per run. Blank lines and lines starting with `;` or `#` are ignored
     #[arg(short, long)]
     origins: PathBuf,
+    /// node type to assume when a seed line is a bare hex object hash rather than an origin
+    /// URL or a full SWHID
+    #[arg(long, value_enum, default_value = "revision")]
+    hash_node_type: HashSeedType,
     /// in case an origin is not found in the graph, this allows the script to attempt to find it
     /// with another protocol (https:// <-> git://)
     #[arg(short = 'p', long, default_value_t = false)]
     allow_protocol_variations: bool,
     /// path to folder or file name for the output. If any origin is not found in the graph,
-    /// a file named `origin_errors.txt` will be written in the same path
+    /// a file named `origin_errors.txt` will be written in the same path. A checksum
+    /// sidecar is written alongside it at `<output>.sha256`
     #[arg(short = 'O', long)]
     output: PathBuf,
+    /// buffer the whole subgraph and sort it before writing, so the output (and its
+    /// checksum) is stable across runs regardless of BFS visitation order. Without this,
+    /// nodes are streamed to disk as they are discovered, which uses less memory but
+    /// makes the output order (and thus the checksum) depend on traversal order
+    #[arg(long, default_value_t = false)]
+    sorted: bool,
+    /// number of worker threads to traverse seeds with. Seeds are split evenly across
+    /// threads that share one global claimed-node set, so a node claimed by one thread's
+    /// traversal is never re-walked by another. Pass 1 to traverse sequentially
+    #[arg(long, default_value_t = default_threads())]
+    threads: usize,
+    /// direction to expand the traversal frontier in: `forward` walks successors from an
+    /// origin (what does this project contain), `backward` walks predecessors from a seed
+    /// node (who contains this artifact)
+    #[arg(long, value_enum, default_value = "forward")]
+    direction: Direction,
+    /// record nodes of this type but don't enqueue their neighbors, so the traversal stops
+    /// at that layer of the graph (e.g. `directory` to record each revision's root
+    /// directory without walking into the file tree below it)
+    #[arg(long, value_enum)]
+    stop_at: Option<StopAtNodeType>,
+    /// halt traversal at this many hops from the seed. Like `--stop-at`, only accepted
+    /// when the manifest yields a single seed; with more than one seed the run fails
+    #[arg(long)]
+    max_depth: Option<usize>,
+}
+
+/// Default `--threads` value: the number of CPUs available to this process.
+fn default_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
 }
 
 pub fn main() -> Result<()> {
@@ -50,41 +186,69 @@ pub fn main() -> Result<()> {
     debug!("Debug logging ON...");
 
     info!("Loading origins...");
-    let origins_lines = lines_from_file(args.origins).expect("Unable to read origins file");
-
-    info!("Loading graph...");
-    let graph = graph::SwhUnidirectionalGraph::new(args.graph)
-        .context("Could not load graph")?
-        .init_properties()
-        .load_properties(|properties| properties.load_maps::<DynMphf>())
-        .context("Could not load graph properties")?;
-
-    let (subgraph_nodes, unknown_origins) =
-        process_origins_and_build_subgraph(&graph, origins_lines, args.allow_protocol_variations);
+    let seeds = read_manifest(&args.origins).context("Unable to read origins manifest")?;
 
     debug!(
-        "Writing list of nodes to '{}'...",
-        args.output.as_path().display()
+        "Writing subgraph nodes to '{}' ({})...",
+        args.output.as_path().display(),
+        if args.sorted { "sorted at the end" } else { "streamed as they are discovered" }
     );
+    let output_file = File::create(&args.output)
+        .with_context(|| format!("could not create '{}'", args.output.as_path().display()))?;
+    let mut sink = SubgraphSink::new(BufWriter::new(output_file), args.sorted);
+
+    let allow_protocol_variations = args.allow_protocol_variations;
+    let hash_node_type = args.hash_node_type.into();
+    let stop_at = args.stop_at.map(NodeType::from);
+    let max_depth = args.max_depth;
+    let threads = args.threads;
 
-    // Call the function and handle the result
-    match write_items_to_file(
-        subgraph_nodes
-            .iter()
-            // convert NodeID into SWHID
-            .map(|node| graph.properties().swhid(*node)),
-        args.output.clone(),
-    ) {
-        Ok(_) => info!(
-            "Successfully wrote list of nodes to '{}'.",
-            args.output.as_path().display()
-        ),
-        Err(e) => error!(
-            "Error writing to file '{}': {}",
-            args.output.as_path().display(),
-            e
-        ),
+    // Only load the backward indexes when a backward traversal was actually requested:
+    // a `SwhBidirectionalGraph` roughly doubles the on-disk/memory footprint compared to
+    // the forward-only `SwhUnidirectionalGraph` the default path uses.
+    let unknown_origins = match args.direction {
+        Direction::Forward => {
+            info!("Loading graph (forward)...");
+            let graph = graph::SwhUnidirectionalGraph::new(args.graph)
+                .context("Could not load graph")?
+                .init_properties()
+                .load_properties(|properties| properties.load_maps::<DynMphf>())
+                .context("Could not load graph properties")?;
+            let opts = TraversalOptions {
+                allow_protocol_variations,
+                hash_node_type,
+                neighbors: forward_neighbors,
+                stop_at,
+                max_depth,
+            };
+            process_origins_and_build_subgraph(&graph, seeds, threads, opts, &sink)
+        }
+        Direction::Backward => {
+            info!("Loading graph (backward)...");
+            let graph = graph::SwhBidirectionalGraph::new(args.graph)
+                .context("Could not load graph")?
+                .init_properties()
+                .load_properties(|properties| properties.load_maps::<DynMphf>())
+                .context("Could not load graph properties")?;
+            let opts = TraversalOptions {
+                allow_protocol_variations,
+                hash_node_type,
+                neighbors: backward_neighbors,
+                stop_at,
+                max_depth,
+            };
+            process_origins_and_build_subgraph(&graph, seeds, threads, opts, &sink)
+        }
     }
+    .with_context(|| format!("failed writing to '{}'", args.output.as_path().display()))?;
+
+    let node_count = sink
+        .finish(&args.output)
+        .with_context(|| format!("failed writing checksum sidecar for '{}'", args.output.display()))?;
+    info!(
+        "Successfully wrote {node_count} nodes to '{}'.",
+        args.output.as_path().display()
+    );
 
     // if there are origins that failed to be found
     if !unknown_origins.is_empty() {
@@ -102,21 +266,151 @@ pub fn main() -> Result<()> {
     Ok(())
 }
 
-fn process_origins_and_build_subgraph<G, I>(
+/// Writes discovered subgraph nodes out to `W` and feeds them into a rolling SHA-256
+/// hasher, so the run ends with a `<output>.sha256` sidecar for a cheap integrity/repro
+/// check between two extraction runs.
+///
+/// In streaming mode (the default) each node is written and hashed as soon as it's
+/// recorded, keeping memory proportional to the traversal frontier rather than the whole
+/// subgraph. In `--sorted` mode nodes are buffered and sorted before writing, trading
+/// that memory saving for an output (and checksum) that no longer depends on BFS
+/// visitation order. `record` takes `&self` (guarded by a single mutex) so the sink can be
+/// shared across the worker threads of a parallel traversal.
+struct SubgraphSink<W: Write> {
+    state: Mutex<SubgraphSinkState<W>>,
+    count: AtomicUsize,
+}
+
+struct SubgraphSinkState<W: Write> {
+    writer: W,
+    hasher: Sha256,
+    sorted_buffer: Option<Vec<String>>,
+}
+
+impl<W: Write> SubgraphSink<W> {
+    fn new(writer: W, sorted: bool) -> Self {
+        Self {
+            state: Mutex::new(SubgraphSinkState {
+                writer,
+                hasher: Sha256::new(),
+                sorted_buffer: sorted.then(Vec::new),
+            }),
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records a newly discovered SWHID.
+    fn record(&self, swhid: String) -> io::Result<()> {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let mut state = self.state.lock().expect("subgraph sink mutex poisoned");
+        match &mut state.sorted_buffer {
+            Some(buffer) => buffer.push(swhid),
+            None => Self::write_and_hash(&mut state.writer, &mut state.hasher, &swhid)?,
+        }
+        Ok(())
+    }
+
+    /// The number of nodes recorded so far.
+    fn len(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn write_and_hash(writer: &mut W, hasher: &mut Sha256, swhid: &str) -> io::Result<()> {
+        writeln!(writer, "{swhid}")?;
+        hasher.update(swhid.as_bytes());
+        hasher.update(b"\n");
+        Ok(())
+    }
+
+    /// Flushes the output (sorting it first in `--sorted` mode), writes the
+    /// `<output_path>.sha256` sidecar next to it, and returns the number of nodes written.
+    fn finish(&mut self, output_path: &Path) -> Result<usize> {
+        let state = self.state.get_mut().expect("subgraph sink mutex poisoned");
+        if let Some(mut buffer) = state.sorted_buffer.take() {
+            buffer.sort_unstable();
+            for swhid in &buffer {
+                Self::write_and_hash(&mut state.writer, &mut state.hasher, swhid)?;
+            }
+        }
+        state.writer.flush()?;
+
+        let mut sidecar_name = output_path.as_os_str().to_owned();
+        sidecar_name.push(".sha256");
+        let sidecar_path = PathBuf::from(sidecar_name);
+        let mut sidecar = File::create(&sidecar_path)
+            .with_context(|| format!("could not create '{}'", sidecar_path.display()))?;
+        let digest = std::mem::take(&mut state.hasher).finalize();
+        let count = self.len();
+        writeln!(sidecar, "{}  {}", hex_encode(&digest), count)?;
+
+        Ok(count)
+    }
+}
+
+/// A lock-free concurrent bitset used as the global "claimed" set during a parallel
+/// traversal: claiming a node is a single atomic fetch-or, so a node claimed by one
+/// worker's traversal immediately stops every other worker from re-expanding it.
+struct AtomicBitset {
+    words: Vec<AtomicU64>,
+}
+
+impl AtomicBitset {
+    fn new(len: usize) -> Self {
+        Self {
+            words: (0..len.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Atomically claims `index`, returning `true` if this call is the one that claimed
+    /// it (i.e. it wasn't already claimed).
+    fn claim(&self, index: usize) -> bool {
+        let mask = 1u64 << (index % 64);
+        let previous = self.words[index / 64].fetch_or(mask, Ordering::AcqRel);
+        previous & mask == 0
+    }
+}
+
+/// Hex-encodes a digest, since `GenericArray` doesn't implement `LowerHex` directly.
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Traverses the graph from every seed in `origins`, writing every newly-discovered node to
+/// `sink`. Seeds are distributed across `num_threads` worker threads (or handled inline when
+/// `num_threads <= 1`), all claiming nodes from a single shared [`AtomicBitset`] so that
+/// overlapping subgraphs between seeds, including those assigned to different threads, are
+/// only ever visited once.
+///
+/// `--stop-at`/`--max-depth` are rejected outright for more than one seed: claiming is
+/// first-come-first-served, so whichever seed's traversal reaches a shared node *first*
+/// (in arbitrary manifest/thread-scheduling order) decides whether that node's neighbors
+/// get expanded, even if another seed would have reached the same node at a shallower
+/// depth with more of the graph left to discover beyond it. That makes the result depend
+/// on scheduling rather than on the graph, so it's rejected rather than silently producing
+/// a non-reproducible subgraph.
+fn process_origins_and_build_subgraph<G, W>(
     graph: &G,
-    origins: I,
-    allow_protocol_variations: bool,
-) -> (HashSet<usize>, Vec<String>)
+    origins: Vec<String>,
+    num_threads: usize,
+    opts: TraversalOptions<G>,
+    sink: &SubgraphSink<W>,
+) -> Result<Vec<String>>
 where
-    G: SwhGraphWithProperties + SwhForwardGraph,
+    G: SwhGraphWithProperties + Sync,
     G::Maps: properties::Maps,
-    I: Iterator<Item = Result<String, std::io::Error>>,
+    W: Write + Send,
 {
-    let graph_props = graph.properties();
-    let num_nodes = graph.num_nodes();
+    if (opts.stop_at.is_some() || opts.max_depth.is_some()) && origins.len() > 1 {
+        anyhow::bail!(
+            "--stop-at and --max-depth are only supported with a single seed: with a shared \
+             claimed-node set, the seed that reaches a node first decides whether it gets \
+             expanded, so a second seed with a shorter path to the same node could be \
+             silently truncated"
+        );
+    }
 
-    let mut subgraph_nodes = HashSet::new();
-    let mut unknown_origins = vec![];
+    let num_nodes = graph.num_nodes();
+    let claimed = AtomicBitset::new(num_nodes);
 
     let mut pl = progress_logger!(
         display_memory = true,
@@ -125,95 +419,234 @@ where
         expected_updates = Some(num_nodes),
     );
     pl.start("visiting graph ...");
+    let pl = Mutex::new(pl);
 
-    for origin_result in origins {
-        if origin_result.is_err() {
-            let err = origin_result.err().unwrap();
-            error!("failed reading line from origins file: {err}");
-            continue;
+    let result = if num_threads <= 1 {
+        process_seed_chunk(graph, &origins, opts, &claimed, &pl, sink)
+    } else {
+        // Split seeds as evenly as possible across the worker pool; each chunk is processed
+        // by its own thread, with `claimed` and `pl` the only state shared between them.
+        let chunk_size = origins.len().div_ceil(num_threads).max(1);
+        let chunks: Vec<&[String]> = origins.chunks(chunk_size).collect();
+
+        let results: Vec<Result<Vec<String>>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = chunks
+                .into_iter()
+                .map(|chunk| scope.spawn(|| process_seed_chunk(graph, chunk, opts, &claimed, &pl, sink)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| anyhow::bail!("worker thread panicked"))
+                })
+                .collect()
+        });
+
+        let mut unknown_origins = vec![];
+        for result in results {
+            unknown_origins.extend(result?);
         }
-        let origin = origin_result.unwrap();
-        let mut origin_swhid = SWHID::from_origin_url(origin.to_owned());
+        Ok(unknown_origins)
+    };
+
+    pl.into_inner()
+        .expect("progress logger mutex poisoned")
+        .done();
+    result
+}
+
+/// Runs the classify/lookup/BFS loop of [`process_origins_and_build_subgraph`] over a single
+/// chunk of seeds, claiming each visited node from the shared `claimed` bitset so that
+/// concurrent chunks never write the same node twice, and reporting progress through the
+/// shared `pl` (locked only for the instant of each update, so chunks on other threads
+/// aren't blocked on it).
+fn process_seed_chunk<G, W, PL>(
+    graph: &G,
+    seeds: &[String],
+    opts: TraversalOptions<G>,
+    claimed: &AtomicBitset,
+    pl: &Mutex<PL>,
+    sink: &SubgraphSink<W>,
+) -> Result<Vec<String>>
+where
+    G: SwhGraphWithProperties,
+    G::Maps: properties::Maps,
+    W: Write,
+    PL: ProgressLog,
+{
+    let graph_props = graph.properties();
+    let num_nodes = graph.num_nodes();
+
+    let mut unknown_origins = vec![];
+
+    for seed_line in seeds {
+        let seed_line = seed_line.to_owned();
+
+        // Classify the seed line: an explicit SWHID or bare hash is looked up directly; an
+        // origin URL is first hashed into an origin SWHID, with protocol variations retried
+        // below if the lookup fails.
+        let (mut seed_swhid, origin_for_retry) = match classify_seed_line(
+            &seed_line,
+            opts.hash_node_type,
+        ) {
+            Ok(Seed::Node(swhid)) => (swhid, None),
+            Ok(Seed::Origin(url)) => (SWHID::from_origin_url(url.clone()), Some(url)),
+            Err(err) => {
+                error!("failed to parse seed '{seed_line}': {err}");
+                unknown_origins.push(seed_line);
+                continue;
+            }
+        };
 
         // Lookup SWHID
-        info!("looking up SWHID {} ...", origin);
-        let mut node_id_lookup = graph_props.node_id(origin_swhid);
-
-        if node_id_lookup.is_err() && allow_protocol_variations {
-            warn!("origin {origin} not in graph. Will look for other protocols");
-            // try with other protocols
-            if origin.contains("git://") || origin.contains("https://") {
-                // try to switch the protocol. Only https and git available
-                let alternative_origin = if origin.contains("git://") {
-                    origin.replace("git://", "https://")
-                } else if origin.contains("https://") {
-                    origin.replace("https://", "git://")
-                } else {
-                    origin.to_owned()
-                };
-
-                origin_swhid = SWHID::from_origin_url(alternative_origin.to_owned());
-
-                node_id_lookup = graph_props.node_id(origin_swhid);
-                if node_id_lookup.is_ok() {
-                    debug!("origin found with different protocol: {origin}");
+        info!("looking up SWHID {} ...", seed_line);
+        let mut node_id_lookup = graph_props.node_id(seed_swhid);
+
+        if node_id_lookup.is_err() && opts.allow_protocol_variations {
+            if let Some(origin) = &origin_for_retry {
+                warn!("origin {origin} not in graph. Will look for other protocols");
+                // try with other protocols
+                if origin.contains("git://") || origin.contains("https://") {
+                    // try to switch the protocol. Only https and git available
+                    let alternative_origin = if origin.contains("git://") {
+                        origin.replace("git://", "https://")
+                    } else if origin.contains("https://") {
+                        origin.replace("https://", "git://")
+                    } else {
+                        origin.to_owned()
+                    };
+
+                    seed_swhid = SWHID::from_origin_url(alternative_origin.to_owned());
+
+                    node_id_lookup = graph_props.node_id(seed_swhid);
+                    if node_id_lookup.is_ok() {
+                        debug!("origin found with different protocol: {origin}");
+                    }
                 }
             }
         }
 
-        // if node_id is still err, attempts to switch protocols failed
-        // the original url from the origins file should be logged
+        // if node_id is still err, attempts to switch protocols failed (or the seed was a
+        // SWHID/hash to begin with); the original seed line should be logged
         let Ok(node_id) = node_id_lookup else {
-            error!("origin {origin} not in graph");
-            unknown_origins.push(origin);
+            error!("seed '{seed_line}' not in graph");
+            unknown_origins.push(seed_line);
             continue;
         };
         debug!("obtained node ID {node_id} ...");
         assert!(node_id < num_nodes);
 
-        // Setup a queue and a visited AdaptiveNodeSet for the visits
-        let mut visited = AdaptiveNodeSet::new(num_nodes);
-        let mut queue: VecDeque<usize> = VecDeque::new();
+        // another thread (or an earlier seed in this chunk) may already have visited this
+        // node; that traversal records it and claims its whole subtree, so there's nothing
+        // left to do for this seed.
+        if !claimed.claim(node_id) {
+            debug!("seed '{seed_line}' already visited from another seed, skipping");
+            continue;
+        }
 
-        queue.push_back(node_id);
+        // queue entries are (node, depth from this seed), so `--max-depth` can be enforced
+        // per seed rather than globally across the whole traversal
+        let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+        queue.push_back((node_id, 0));
 
-        // Setup the progress logger for
         let mut visited_nodes = 0;
 
-        debug!("starting bfs for the origin: {origin}");
-
-        // iterative BFS
-        while let Some(current_node) = queue.pop_front() {
-            if log::log_enabled!(Level::Debug) {
-                let id = graph.properties().swhid(current_node);
-                debug!("visited: {id}");
-            } // add current_node to the external results hashset
-            let new = subgraph_nodes.insert(current_node);
-            //  only visit children if this node is new
-            if new {
-                visited_nodes += 1;
-                for succ in graph.successors(current_node) {
-                    if !visited.contains(succ) {
-                        queue.push_back(succ);
-                        visited.insert(succ);
-                        pl.light_update();
-                    }
+        debug!("starting bfs for the seed: {seed_line}");
+
+        // iterative BFS, walking whichever direction `opts.neighbors` was built for
+        while let Some((current_node, depth)) = queue.pop_front() {
+            let id = graph.properties().swhid(current_node);
+            debug!("visited: {id}");
+            sink.record(id.to_string())
+                .with_context(|| format!("failed writing node '{id}' to output"))?;
+            visited_nodes += 1;
+
+            // `--stop-at` records a node but doesn't expand past it; `--max-depth` stops
+            // expansion once the bound is reached, regardless of node type
+            let stopped_at_type = opts.stop_at.is_some_and(|node_type| id.node_type == node_type);
+            let stopped_at_depth = opts.max_depth.is_some_and(|max_depth| depth >= max_depth);
+            if stopped_at_type || stopped_at_depth {
+                continue;
+            }
+
+            for neighbor in (opts.neighbors)(graph, current_node) {
+                if claimed.claim(neighbor) {
+                    queue.push_back((neighbor, depth + 1));
+                    pl.lock().expect("progress logger mutex poisoned").light_update();
                 }
-            } else if log::log_enabled!(Level::Debug) {
-                debug!(
-                    "stopping bfs because this node was foud in a previous bfs run (from another origin) {current_node}"
-                );
             }
         }
 
         if log::log_enabled!(Level::Info) {
-            pl.update_and_display();
+            pl.lock()
+                .expect("progress logger mutex poisoned")
+                .update_and_display();
         }
-        info!("visit from {origin} completed after visiting {visited_nodes} nodes.");
+
+        info!("visit from {seed_line} completed after visiting {visited_nodes} nodes.");
     }
-    pl.done();
 
-    (subgraph_nodes, unknown_origins)
+    Ok(unknown_origins)
+}
+
+/// A classified traversal seed: either an origin URL (to be hashed into an origin SWHID)
+/// or a node to look up directly.
+enum Seed {
+    Origin(String),
+    Node(SWHID),
+}
+
+/// Classifies a seed line as a full SWHID, a bare hex object hash, or an origin URL, in
+/// that order. Bare hashes are assumed to be of `hash_node_type`, since the hash alone
+/// does not carry type information.
+fn classify_seed_line(line: &str, hash_node_type: NodeType) -> Result<Seed> {
+    if line.starts_with("swh:") {
+        let swhid = line
+            .parse::<SWHID>()
+            .map_err(|e| anyhow::anyhow!("invalid SWHID: {e}"))?;
+        Ok(Seed::Node(swhid))
+    } else if line.len() == 40 && line.bytes().all(|b| b.is_ascii_hexdigit()) {
+        let hash = parse_hex_object_id(line).context("invalid hex object hash")?;
+        Ok(Seed::Node(SWHID {
+            namespace_version: 1,
+            node_type: hash_node_type,
+            hash,
+        }))
+    } else {
+        Ok(Seed::Origin(line.to_owned()))
+    }
+}
+
+/// Error returned when a 2-hex-character chunk of a bare object hash isn't valid hex.
+#[derive(Debug)]
+struct InvalidHexByteError([u8; 2]);
+
+impl Display for InvalidHexByteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid hex chunk '{}{}'",
+            self.0[0] as char, self.0[1] as char
+        )
+    }
+}
+
+impl std::error::Error for InvalidHexByteError {}
+
+/// Parses a 40-character hex object hash into its raw 20-byte form, two hex characters
+/// (one byte) at a time, the same way git object IDs are commonly parsed.
+fn parse_hex_object_id(hex: &str) -> Result<[u8; 20], InvalidHexByteError> {
+    let bytes = hex.as_bytes();
+    let mut id = [0u8; 20];
+    for (i, slot) in id.iter_mut().enumerate() {
+        let pair = &bytes[i * 2..i * 2 + 2];
+        // `line.bytes().all(is_ascii_hexdigit)` guarantees this slice is valid UTF-8.
+        let chunk = std::str::from_utf8(pair).expect("hex digits are valid UTF-8");
+        *slot = u8::from_str_radix(chunk, 16).map_err(|_| InvalidHexByteError([pair[0], pair[1]]))?;
+    }
+    Ok(id)
 }
 
 // write_items_to_file can take hanshmaps and vecs
@@ -237,11 +670,85 @@ where
     Ok(())
 }
 
-fn lines_from_file(filename: impl AsRef<Path>) -> io::Result<Lines<BufReader<File>>> {
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-    // returns the iterator from BufReader::lines()
-    Ok(reader.lines())
+/// Reads an extraction manifest: one seed per line, modeled on Mercurial's layered config
+/// files. `%include <path>` splices in another manifest (resolved relative to the
+/// including file, with cycle detection), `%unset <seed>` removes a previously added
+/// seed, and blank or comment (`;`/`#`) lines are ignored. Returns an ordered,
+/// de-duplicated seed list ready to feed into `process_origins_and_build_subgraph`.
+fn read_manifest(path: impl AsRef<Path>) -> Result<Vec<String>> {
+    let regexes = ManifestRegexes {
+        include_re: Regex::new(r"^%include\s+(?P<path>\S+)\s*$").expect("valid regex"),
+        unset_re: Regex::new(r"^%unset\s+(?P<seed>\S+)\s*$").expect("valid regex"),
+        comment_re: Regex::new(r"^(;|#|\s*$)").expect("valid regex"),
+    };
+
+    let mut seeds = vec![];
+    let mut seen = HashSet::new();
+    let mut including = HashSet::new();
+    read_manifest_into(
+        path.as_ref(),
+        &regexes,
+        &mut seeds,
+        &mut seen,
+        &mut including,
+    )?;
+    Ok(seeds)
+}
+
+/// The regexes used to parse a manifest line, bundled together since every recursive
+/// `%include` call needs all three.
+struct ManifestRegexes {
+    include_re: Regex,
+    unset_re: Regex,
+    comment_re: Regex,
+}
+
+fn read_manifest_into(
+    path: &Path,
+    regexes: &ManifestRegexes,
+    seeds: &mut Vec<String>,
+    seen: &mut HashSet<String>,
+    including: &mut HashSet<PathBuf>,
+) -> Result<()> {
+    let canonical_path = path
+        .canonicalize()
+        .with_context(|| format!("could not resolve manifest '{}'", path.display()))?;
+    if !including.insert(canonical_path.clone()) {
+        anyhow::bail!("cyclic %include detected at '{}'", path.display());
+    }
+
+    let file = File::open(path).with_context(|| format!("could not open manifest '{}'", path.display()))?;
+    for line in BufReader::new(file).lines() {
+        let line = line.with_context(|| format!("failed reading line from '{}'", path.display()))?;
+
+        if regexes.comment_re.is_match(&line) {
+            continue;
+        }
+
+        if let Some(captures) = regexes.include_re.captures(&line) {
+            let included_path = path
+                .parent()
+                .unwrap_or_else(|| Path::new("."))
+                .join(&captures["path"]);
+            read_manifest_into(&included_path, regexes, seeds, seen, including)?;
+            continue;
+        }
+
+        if let Some(captures) = regexes.unset_re.captures(&line) {
+            let unset_seed = &captures["seed"];
+            if seen.remove(unset_seed) {
+                seeds.retain(|seed| seed != unset_seed);
+            }
+            continue;
+        }
+
+        if seen.insert(line.clone()) {
+            seeds.push(line);
+        }
+    }
+
+    including.remove(&canonical_path);
+    Ok(())
 }
 
 #[cfg(test)]
@@ -336,41 +843,268 @@ mod tests {
 
         let graph = builder.done().unwrap();
 
+        // Runs the subgraph builder single-threaded against an in-memory sink, returning
+        // the node count written and the unknown seeds, without touching the filesystem.
+        fn run<G>(
+            graph: &G,
+            origins: Vec<String>,
+            allow_protocol_variations: bool,
+        ) -> (usize, Vec<String>)
+        where
+            G: SwhGraphWithProperties + SwhForwardGraph + Sync,
+            G::Maps: properties::Maps,
+        {
+            let sink = SubgraphSink::new(Vec::new(), false);
+            let opts = TraversalOptions {
+                allow_protocol_variations,
+                hash_node_type: NodeType::Revision,
+                neighbors: forward_neighbors,
+                stop_at: None,
+                max_depth: None,
+            };
+            let unknown = process_origins_and_build_subgraph(graph, origins, 1, opts, &sink).unwrap();
+            (sink.len(), unknown)
+        }
+
         let origins = vec![
-            Ok("https://example.com/repo1".to_string()),
+            "https://example.com/repo1".to_string(),
             // this one should be found with allow_protocol_variations
-            Ok("git://example.com/repo2".to_string()),
-            Ok("https://unknown.com/repo".to_string()),
+            "git://example.com/repo2".to_string(),
+            "https://unknown.com/repo".to_string(),
         ];
-        let (subgraph_nodes, unknown_origins) =
-            process_origins_and_build_subgraph(&graph, origins.into_iter(), true);
+        let (node_count, unknown_origins) = run(&graph, origins, true);
 
         // Check that we found the expected nodes
-        assert_eq!(subgraph_nodes.len(), 7); // should contain both origins and the revision
+        assert_eq!(node_count, 7); // should contain both origins and the revision
         assert_eq!(unknown_origins.len(), 1); // the unknown origin
         assert_eq!(unknown_origins[0], "https://unknown.com/repo");
 
         // Test with empty input
-        let (empty_nodes, empty_unknown) =
-            process_origins_and_build_subgraph(&graph, iter::empty(), false);
-        assert!(empty_nodes.is_empty());
+        let (empty_count, empty_unknown) = run(&graph, vec![], false);
+        assert_eq!(empty_count, 0);
         assert!(empty_unknown.is_empty());
 
         // Test with only invalid origins
         let invalid_origins = vec![
-            Ok("https://invalid1.com".to_string()),
-            Ok("https://invalid2.com".to_string()),
+            "https://invalid1.com".to_string(),
+            "https://invalid2.com".to_string(),
         ];
-        let (invalid_nodes, invalid_unknown) =
-            process_origins_and_build_subgraph(&graph, invalid_origins.into_iter(), false);
-        assert!(invalid_nodes.is_empty());
+        let (invalid_count, invalid_unknown) = run(&graph, invalid_origins, false);
+        assert_eq!(invalid_count, 0);
         assert_eq!(invalid_unknown.len(), 2);
 
-        // Test with only invalid origins
-        let disjoint_origins = vec![Ok("https://example.com/discinnected".to_string())];
-        let (disjoint_nodes, disjoint_unknown) =
-            process_origins_and_build_subgraph(&graph, disjoint_origins.into_iter(), false);
+        // Test with a disjoint subgraph
+        let disjoint_origins = vec!["https://example.com/discinnected".to_string()];
+        let (disjoint_count, disjoint_unknown) = run(&graph, disjoint_origins, false);
         assert!(disjoint_unknown.is_empty());
-        assert_eq!(disjoint_nodes.len(), 2);
+        assert_eq!(disjoint_count, 2);
+
+        // A seed given as a full SWHID should be looked up directly, without going
+        // through the origin-hashing path.
+        let swhid_origins = vec!["swh:1:rev:0000000000000000000000000000000000000004".to_string()];
+        let (swhid_count, swhid_unknown) = run(&graph, swhid_origins, false);
+        assert!(swhid_unknown.is_empty());
+        assert_eq!(swhid_count, 1);
+
+        // A bare 40-character hex hash should be looked up as `hash_node_type`.
+        let hash_origins = vec!["0000000000000000000000000000000000000004".to_string()];
+        let (hash_count, hash_unknown) = run(&graph, hash_origins, false);
+        assert!(hash_unknown.is_empty());
+        assert_eq!(hash_count, 1);
+
+        // The same traversal, split across multiple worker threads, must find exactly the
+        // same nodes: the shared claimed-node bitset is what keeps the result independent
+        // of how seeds are distributed across threads.
+        let parallel_origins = vec![
+            "https://example.com/repo1".to_string(),
+            "https://example.com/repo2".to_string(),
+        ];
+        let sink = SubgraphSink::new(Vec::new(), false);
+        let parallel_opts = TraversalOptions {
+            allow_protocol_variations: false,
+            hash_node_type: NodeType::Revision,
+            neighbors: forward_neighbors,
+            stop_at: None,
+            max_depth: None,
+        };
+        let unknown =
+            process_origins_and_build_subgraph(&graph, parallel_origins, 4, parallel_opts, &sink)
+                .unwrap();
+        assert!(unknown.is_empty());
+        assert_eq!(sink.len(), 7); // same total as the single-threaded repo1+repo2 case above
+
+        // A backward traversal from a revision should recover the snapshots and origins
+        // that reference it.
+        let backward_origins = vec!["swh:1:rev:0000000000000000000000000000000000000005".to_string()];
+        let backward_sink = SubgraphSink::new(Vec::new(), false);
+        let backward_opts = TraversalOptions {
+            allow_protocol_variations: false,
+            hash_node_type: NodeType::Revision,
+            neighbors: backward_neighbors,
+            stop_at: None,
+            max_depth: None,
+        };
+        let backward_unknown = process_origins_and_build_subgraph(
+            &graph,
+            backward_origins,
+            1,
+            backward_opts,
+            &backward_sink,
+        )
+        .unwrap();
+        assert!(backward_unknown.is_empty());
+        // rev5 <- snp2, snp3; snp2 <- ori0, ori1; snp3 <- ori0; plus rev5 itself, for 5
+        // nodes total.
+        assert_eq!(backward_sink.len(), 5);
+
+        // `--stop-at snapshot` should record the snapshots but not walk back into the origins.
+        let stop_at_origins = vec!["swh:1:rev:0000000000000000000000000000000000000005".to_string()];
+        let stop_at_sink = SubgraphSink::new(Vec::new(), false);
+        let stop_at_opts = TraversalOptions {
+            allow_protocol_variations: false,
+            hash_node_type: NodeType::Revision,
+            neighbors: backward_neighbors,
+            stop_at: Some(NodeType::Snapshot),
+            max_depth: None,
+        };
+        process_origins_and_build_subgraph(&graph, stop_at_origins, 1, stop_at_opts, &stop_at_sink)
+            .unwrap();
+        assert_eq!(stop_at_sink.len(), 3); // rev5, snp2, snp3
+
+        // `--max-depth 0` should record only the seed node itself.
+        let max_depth_origins = vec!["swh:1:rev:0000000000000000000000000000000000000005".to_string()];
+        let max_depth_sink = SubgraphSink::new(Vec::new(), false);
+        let max_depth_opts = TraversalOptions {
+            allow_protocol_variations: false,
+            hash_node_type: NodeType::Revision,
+            neighbors: backward_neighbors,
+            stop_at: None,
+            max_depth: Some(0),
+        };
+        process_origins_and_build_subgraph(
+            &graph,
+            max_depth_origins,
+            1,
+            max_depth_opts,
+            &max_depth_sink,
+        )
+        .unwrap();
+        assert_eq!(max_depth_sink.len(), 1);
+
+        // Combining more than one seed with `--stop-at`/`--max-depth` is rejected outright,
+        // rather than silently producing a result that depends on seed/thread scheduling.
+        let multi_seed_origins = vec![
+            "swh:1:rev:0000000000000000000000000000000000000005".to_string(),
+            "swh:1:rev:0000000000000000000000000000000000000006".to_string(),
+        ];
+        let rejected_sink = SubgraphSink::new(Vec::new(), false);
+        let rejected_opts = TraversalOptions {
+            allow_protocol_variations: false,
+            hash_node_type: NodeType::Revision,
+            neighbors: backward_neighbors,
+            stop_at: None,
+            max_depth: Some(1),
+        };
+        assert!(process_origins_and_build_subgraph(
+            &graph,
+            multi_seed_origins,
+            1,
+            rejected_opts,
+            &rejected_sink,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_hex_object_id() {
+        let hash = parse_hex_object_id("0000000000000000000000000000000000000004").unwrap();
+        assert_eq!(hash, swhid!(swh:1:rev:0000000000000000000000000000000000000004).hash);
+
+        let err = parse_hex_object_id("zz00000000000000000000000000000000000004").unwrap_err();
+        assert_eq!(err.0, [b'z', b'z']);
+    }
+
+    #[test]
+    fn test_read_manifest() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "swhids-subgrapher-test-read-manifest-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let base_path = dir.join("base.manifest");
+        std::fs::write(
+            &base_path,
+            "; base manifest\nhttps://example.com/repo1\n# a comment\n\nhttps://example.com/repo2\n",
+        )
+        .unwrap();
+
+        let overlay_path = dir.join("overlay.manifest");
+        std::fs::write(
+            &overlay_path,
+            "%include base.manifest\n%unset https://example.com/repo2\nhttps://example.com/repo3\n",
+        )
+        .unwrap();
+
+        let seeds = read_manifest(&overlay_path).unwrap();
+        assert_eq!(
+            seeds,
+            vec![
+                "https://example.com/repo1".to_string(),
+                "https://example.com/repo3".to_string(),
+            ]
+        );
+
+        // A manifest that %includes itself should be rejected rather than looping forever.
+        let cyclic_path = dir.join("cyclic.manifest");
+        std::fs::write(&cyclic_path, "%include cyclic.manifest\n").unwrap();
+        assert!(read_manifest(&cyclic_path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_subgraph_sink() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let output_path = std::env::temp_dir().join(format!(
+            "swhids-subgrapher-test-subgraph-sink-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        // Streaming mode writes (and hashes) nodes in insertion order.
+        let mut sink = SubgraphSink::new(Vec::new(), false);
+        sink.record("b".to_string()).unwrap();
+        sink.record("a".to_string()).unwrap();
+        let streamed_count = sink.finish(&output_path).unwrap();
+        assert_eq!(streamed_count, 2);
+        assert_eq!(
+            String::from_utf8(sink.state.into_inner().unwrap().writer).unwrap(),
+            "b\na\n"
+        );
+        let streamed_digest = std::fs::read_to_string(output_path.with_extension("sha256")).unwrap();
+        assert!(streamed_digest.ends_with("  2\n"));
+
+        // Sorted mode writes (and hashes) the nodes in a deterministic order regardless
+        // of insertion order.
+        let mut sink = SubgraphSink::new(Vec::new(), true);
+        sink.record("b".to_string()).unwrap();
+        sink.record("a".to_string()).unwrap();
+        let sorted_count = sink.finish(&output_path).unwrap();
+        assert_eq!(sorted_count, 2);
+        assert_eq!(
+            String::from_utf8(sink.state.into_inner().unwrap().writer).unwrap(),
+            "a\nb\n"
+        );
+        let sorted_digest = std::fs::read_to_string(output_path.with_extension("sha256")).unwrap();
+        assert_ne!(streamed_digest, sorted_digest);
+
+        std::fs::remove_file(output_path.with_extension("sha256")).unwrap();
     }
 }